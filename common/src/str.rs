@@ -5,6 +5,7 @@ use ascii::{AsciiChar, AsciiStr, AsciiString};
 use core::fmt;
 use core::sync::atomic::Ordering::Relaxed;
 use std::ops::{Bound, RangeBounds};
+use std::sync::OnceLock;
 
 #[cfg(not(target_arch = "wasm32"))]
 #[allow(non_camel_case_types)]
@@ -109,11 +110,24 @@ pub enum PyKindStr<'a> {
     Wtf8(&'a Wtf8),
 }
 
+/// A string with a PEP 393-style fixed-width backing store. The narrowest
+/// representation that fits every code point is chosen at construction time —
+/// Latin-1 (1 byte/char) for max code point ≤ U+00FF, UCS-2 (2 bytes) for
+/// ≤ U+FFFF and UCS-4 (4 bytes) otherwise — so that [`char_len`](Self::char_len)
+/// and [`nth_char`](Self::nth_char) are O(1). A UTF-8/WTF-8 byte view is
+/// materialized lazily the first time a caller needs bytes, and cached.
 #[derive(Debug, Clone)]
 pub struct StrData {
-    data: Box<Wtf8>,
+    /// `char_len * char_size` bytes in native endianness, one fixed-width unit
+    /// per code point.
+    data: Box<[u8]>,
     kind: StrKind,
-    len: StrLen,
+    /// Bytes per code point in `data`: 1 (Latin-1/ASCII), 2 (UCS-2) or 4 (UCS-4).
+    char_size: u8,
+    char_len: usize,
+    grapheme_len: StrLen,
+    /// Lazily materialized WTF-8 view, built on first byte access.
+    wtf8: OnceLock<Box<Wtf8>>,
 }
 
 struct StrLen(PyAtomic<usize>);
@@ -156,39 +170,44 @@ impl Clone for StrLen {
 impl Default for StrData {
     fn default() -> Self {
         Self {
-            data: <Box<Wtf8>>::default(),
+            data: Box::default(),
             kind: StrKind::Ascii,
-            len: StrLen::zero(),
+            char_size: 1,
+            char_len: 0,
+            grapheme_len: StrLen::zero(),
+            wtf8: OnceLock::new(),
         }
     }
 }
 
 impl From<Box<Wtf8>> for StrData {
     fn from(value: Box<Wtf8>) -> Self {
-        // doing the check is ~10x faster for ascii, and is actually only 2% slower worst case for
-        // non-ascii; see https://github.com/RustPython/RustPython/pull/2586#issuecomment-844611532
-        let kind = value.str_kind();
-        unsafe { Self::new_str_unchecked(value, kind) }
+        // Pack into the narrowest fixed-width store and drop the WTF-8 bytes;
+        // a WTF-8 view is materialized on demand only if a caller needs bytes.
+        Self::encode(&value)
     }
 }
 
 impl From<Box<str>> for StrData {
     #[inline]
     fn from(value: Box<str>) -> Self {
-        // doing the check is ~10x faster for ascii, and is actually only 2% slower worst case for
-        // non-ascii; see https://github.com/RustPython/RustPython/pull/2586#issuecomment-844611532
-        let kind = value.str_kind();
-        unsafe { Self::new_str_unchecked(value.into(), kind) }
+        let value: Box<Wtf8> = value.into();
+        value.into()
     }
 }
 
 impl From<Box<AsciiStr>> for StrData {
     #[inline]
     fn from(value: Box<AsciiStr>) -> Self {
+        // ASCII fast path: one byte per char, already its own fixed-width store.
+        let char_len = value.len();
         Self {
-            len: value.len().into(),
-            data: value.into(),
+            data: value.as_bytes().into(),
             kind: StrKind::Ascii,
+            char_size: 1,
+            char_len,
+            grapheme_len: StrLen::uncomputed(),
+            wtf8: OnceLock::new(),
         }
     }
 }
@@ -204,11 +223,8 @@ impl From<char> for StrData {
         if let Ok(ch) = ascii::AsciiChar::from_ascii(ch) {
             ch.into()
         } else {
-            Self {
-                data: ch.to_string().into(),
-                kind: StrKind::Utf8,
-                len: 1.into(),
-            }
+            let value: Box<str> = ch.to_string().into();
+            value.into()
         }
     }
 }
@@ -218,54 +234,148 @@ impl From<CodePoint> for StrData {
         if let Some(ch) = ch.to_char() {
             ch.into()
         } else {
-            Self {
-                data: Wtf8Buf::from(ch).into(),
-                kind: StrKind::Wtf8,
-                len: 1.into(),
-            }
+            let value: Box<Wtf8> = Wtf8Buf::from(ch).into();
+            value.into()
         }
     }
 }
 
 impl StrData {
+    /// Scan `data` once to deduce the [`StrKind`] and the narrowest fixed-width
+    /// representation, then pack it into the backing store.
+    fn encode(data: &Wtf8) -> Self {
+        let mut max = 0u32;
+        let mut ascii = true;
+        let mut surrogate = false;
+        let mut char_len = 0usize;
+        for c in data.code_points() {
+            char_len += 1;
+            let u = c.to_u32();
+            if u > 0x7f {
+                ascii = false;
+            }
+            if c.to_char().is_none() {
+                surrogate = true;
+            }
+            if u > max {
+                max = u;
+            }
+        }
+        let char_size = Self::char_size_for(max);
+        let kind = if ascii {
+            StrKind::Ascii
+        } else if surrogate {
+            StrKind::Wtf8
+        } else {
+            StrKind::Utf8
+        };
+        Self {
+            data: Self::pack(data, char_size, char_len),
+            kind,
+            char_size,
+            char_len,
+            grapheme_len: StrLen::uncomputed(),
+            wtf8: OnceLock::new(),
+        }
+    }
+
+    /// Bytes per code point needed to store a string whose largest code point
+    /// is `max`.
+    #[inline]
+    fn char_size_for(max: u32) -> u8 {
+        if max <= 0xff {
+            1
+        } else if max <= 0xffff {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// Pack `char_len` code points of `data` into a fixed-width backing store of
+    /// `char_size` bytes per code point, in native endianness.
+    fn pack(data: &Wtf8, char_size: u8, char_len: usize) -> Box<[u8]> {
+        let mut bytes = vec![0u8; char_len * char_size as usize];
+        for (i, c) in data.code_points().enumerate() {
+            let u = c.to_u32();
+            let off = i * char_size as usize;
+            match char_size {
+                1 => bytes[off] = u as u8,
+                2 => bytes[off..off + 2].copy_from_slice(&(u as u16).to_ne_bytes()),
+                _ => bytes[off..off + 4].copy_from_slice(&u.to_ne_bytes()),
+            }
+        }
+        bytes.into_boxed_slice()
+    }
+
     /// # Safety
     ///
     /// Given `bytes` must be valid data for given `kind`
     pub unsafe fn new_str_unchecked(data: Box<Wtf8>, kind: StrKind) -> Self {
-        let len = match kind {
-            StrKind::Ascii => data.len().into(),
-            _ => StrLen::uncomputed(),
-        };
-        Self { data, kind, len }
+        let mut this = Self::encode(&data);
+        this.kind = kind;
+        this
     }
 
     /// # Safety
     ///
     /// `char_len` must be accurate.
     pub unsafe fn new_with_char_len(data: Box<Wtf8>, kind: StrKind, char_len: usize) -> Self {
+        // Trust the caller-supplied `char_len` and never recount; only the
+        // largest code point has to be found to size the fixed-width store.
+        let char_size = match kind {
+            StrKind::Ascii => 1,
+            _ => Self::char_size_for(data.code_points().map(|c| c.to_u32()).max().unwrap_or(0)),
+        };
         Self {
-            data,
+            data: Self::pack(&data, char_size, char_len),
             kind,
-            len: char_len.into(),
+            char_size,
+            char_len,
+            grapheme_len: StrLen::uncomputed(),
+            wtf8: OnceLock::new(),
+        }
+    }
+
+    /// Load the raw code-point value stored at `index` with a single indexed
+    /// read from the fixed-width backing store.
+    #[inline]
+    fn load_code(&self, index: usize) -> u32 {
+        let off = index * self.char_size as usize;
+        let d = &self.data;
+        match self.char_size {
+            1 => d[off] as u32,
+            2 => u16::from_ne_bytes([d[off], d[off + 1]]) as u32,
+            _ => u32::from_ne_bytes([d[off], d[off + 1], d[off + 2], d[off + 3]]),
+        }
+    }
+
+    /// Rebuild the WTF-8 byte view from the fixed-width store.
+    #[cold]
+    fn materialize(&self) -> Box<Wtf8> {
+        let mut buf = Wtf8Buf::with_capacity(self.char_len);
+        for i in 0..self.char_len {
+            buf.push(CodePoint::from_u32(self.load_code(i)).unwrap());
         }
+        buf.into_box()
     }
 
     #[inline]
     pub fn as_wtf8(&self) -> &Wtf8 {
-        &self.data
+        self.wtf8.get_or_init(|| self.materialize())
     }
 
     #[inline]
     pub fn as_str(&self) -> Option<&str> {
         self.kind
             .is_utf8()
-            .then(|| unsafe { std::str::from_utf8_unchecked(self.data.as_bytes()) })
+            .then(|| unsafe { std::str::from_utf8_unchecked(self.as_wtf8().as_bytes()) })
     }
 
     pub fn as_ascii(&self) -> Option<&AsciiStr> {
         self.kind
             .is_ascii()
-            .then(|| unsafe { AsciiStr::from_ascii_unchecked(self.data.as_bytes()) })
+            .then(|| unsafe { AsciiStr::from_ascii_unchecked(self.as_wtf8().as_bytes()) })
     }
 
     pub fn kind(&self) -> StrKind {
@@ -276,57 +386,533 @@ impl StrData {
     pub fn as_str_kind(&self) -> PyKindStr<'_> {
         match self.kind {
             StrKind::Ascii => {
-                PyKindStr::Ascii(unsafe { AsciiStr::from_ascii_unchecked(self.data.as_bytes()) })
+                PyKindStr::Ascii(unsafe { AsciiStr::from_ascii_unchecked(self.as_wtf8().as_bytes()) })
             }
             StrKind::Utf8 => {
-                PyKindStr::Utf8(unsafe { std::str::from_utf8_unchecked(self.data.as_bytes()) })
+                PyKindStr::Utf8(unsafe { std::str::from_utf8_unchecked(self.as_wtf8().as_bytes()) })
             }
-            StrKind::Wtf8 => PyKindStr::Wtf8(&self.data),
+            StrKind::Wtf8 => PyKindStr::Wtf8(self.as_wtf8()),
         }
     }
 
+    /// Length of the WTF-8 byte view of this string.
     #[inline]
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.as_wtf8().len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.char_len == 0
     }
 
+    /// Number of code points in this string — O(1), read straight from the
+    /// fixed-width store (`data.len() / char_size`).
     #[inline]
     pub fn char_len(&self) -> usize {
-        match self.len.0.load(Relaxed) {
-            usize::MAX => self._compute_char_len(),
+        self.char_len
+    }
+
+    /// Code point at `index` — O(1) indexed load from the fixed-width store.
+    pub fn nth_char(&self, index: usize) -> CodePoint {
+        CodePoint::from_u32(self.load_code(index)).unwrap()
+    }
+
+    /// Iterate the extended grapheme clusters (UAX #29) of this string as
+    /// `&Wtf8` sub-slices, so that user-visible "character" boundaries match
+    /// what a terminal renders (combining sequences, flag emoji, skin-tone ZWJ
+    /// sequences, and so on).
+    #[inline]
+    pub fn graphemes(&self) -> Graphemes<'_> {
+        graphemes(self.as_wtf8())
+    }
+
+    /// Number of extended grapheme clusters in this string. Computed lazily on
+    /// the first call and cached.
+    pub fn grapheme_len(&self) -> usize {
+        match self.grapheme_len.0.load(Relaxed) {
+            usize::MAX => self._compute_grapheme_len(),
             len => len,
         }
     }
 
     #[cold]
-    fn _compute_char_len(&self) -> usize {
-        let len = if let Some(s) = self.as_str() {
-            // utf8 chars().count() is optimized
-            s.chars().count()
-        } else {
-            self.data.code_points().count()
-        };
-        // len cannot be usize::MAX, since vec.capacity() < sys.maxsize
-        self.len.0.store(len, Relaxed);
+    fn _compute_grapheme_len(&self) -> usize {
+        let len = self.graphemes().count();
+        self.grapheme_len.0.store(len, Relaxed);
         len
     }
 
-    pub fn nth_char(&self, index: usize) -> CodePoint {
-        match self.as_str_kind() {
-            PyKindStr::Ascii(s) => s[index].into(),
-            PyKindStr::Utf8(s) => s.chars().nth(index).unwrap().into(),
-            PyKindStr::Wtf8(w) => w.code_points().nth(index).unwrap(),
+    /// Number of terminal columns needed to display this string, following the
+    /// Unicode East Asian Width property (a `wcswidth`-style measure): wide
+    /// (`W`/`F`) code points count as 2, zero-width code points as 0, and the
+    /// rest as 1. Returns `None` if the string contains a non-printable control
+    /// character, which has no defined column width.
+    pub fn width(&self) -> Option<usize> {
+        // all printable ascii is exactly one column per byte; a control byte
+        // has no width, so fall through to the slow path to signal it.
+        if let PyKindStr::Ascii(s) = self.as_str_kind() {
+            if s.as_bytes().iter().all(|&b| char_width((b as char).into()).is_some()) {
+                return Some(self.len());
+            }
+        }
+        (0..self.char_len).try_fold(0usize, |acc, i| {
+            char_width(CodePoint::from_u32(self.load_code(i)).unwrap()).map(|w| acc + w)
+        })
+    }
+
+    /// Decode a potentially ill-formed UTF-16 slice into a string, losslessly.
+    ///
+    /// Valid surrogate pairs are combined into their scalar code point, while
+    /// *lone* surrogates are preserved as WTF-8 (yielding [`StrKind::Wtf8`])
+    /// rather than being replaced. This is the decode half of the
+    /// `surrogatepass` codec and of `PyUnicode_FromWideChar` on Windows.
+    pub fn from_utf16(wide: &[u16]) -> Self {
+        let mut buf = Wtf8Buf::with_capacity(wide.len());
+        let mut iter = wide.iter().copied().peekable();
+        while let Some(unit) = iter.next() {
+            if (0xd800..=0xdbff).contains(&unit) {
+                // A high surrogate followed by a low surrogate is one scalar.
+                if let Some(&low) = iter.peek() {
+                    if (0xdc00..=0xdfff).contains(&low) {
+                        iter.next();
+                        let c = 0x1_0000
+                            + ((u32::from(unit) - 0xd800) << 10)
+                            + (u32::from(low) - 0xdc00);
+                        buf.push(CodePoint::from_u32(c).unwrap());
+                        continue;
+                    }
+                }
+            }
+            // Anything else, including a lone surrogate, is kept verbatim.
+            buf.push(CodePoint::from_u32(u32::from(unit)).unwrap());
         }
+        buf.into_box().into()
+    }
+
+    /// Re-encode this string as UTF-16, emitting any lone surrogate it holds
+    /// unchanged. Inverse of [`from_utf16`](Self::from_utf16).
+    pub fn encode_utf16(&self) -> Vec<u16> {
+        let mut out = Vec::with_capacity(self.char_len);
+        let mut buf = [0u16; 2];
+        for i in 0..self.char_len {
+            let u = self.load_code(i);
+            match char::from_u32(u) {
+                Some(ch) => out.extend_from_slice(ch.encode_utf16(&mut buf)),
+                None => out.push(u as u16),
+            }
+        }
+        out
+    }
+
+    /// Decode a platform wide-character buffer (`wchar_t`). On Windows `wchar_t`
+    /// is 16-bit, so this is [`from_utf16`](Self::from_utf16); elsewhere it is
+    /// 32-bit and each unit is taken as a single code point, and units that are
+    /// not valid code points are skipped.
+    #[cfg(windows)]
+    pub fn from_wide(wide: &[wchar_t]) -> Self {
+        let units: Vec<u16> = wide.iter().map(|&w| w as u16).collect();
+        Self::from_utf16(&units)
+    }
+
+    #[cfg(not(windows))]
+    pub fn from_wide(wide: &[wchar_t]) -> Self {
+        let mut buf = Wtf8Buf::with_capacity(wide.len());
+        for &w in wide {
+            if let Some(c) = CodePoint::from_u32(w as u32) {
+                buf.push(c);
+            }
+        }
+        buf.into_box().into()
+    }
+
+    /// Encode this string into a platform wide-character buffer (`wchar_t`).
+    /// Inverse of [`from_wide`](Self::from_wide).
+    #[cfg(windows)]
+    pub fn to_wide(&self) -> Vec<wchar_t> {
+        self.encode_utf16().into_iter().map(|u| u as wchar_t).collect()
+    }
+
+    #[cfg(not(windows))]
+    pub fn to_wide(&self) -> Vec<wchar_t> {
+        (0..self.char_len)
+            .map(|i| self.load_code(i) as wchar_t)
+            .collect()
     }
 }
 
+/// Display width of a single code point per the Unicode East Asian Width
+/// property. Control characters return `None`; combining marks and other
+/// zero-width code points return `Some(0)`; `W`/`F` code points return
+/// `Some(2)`; everything else returns `Some(1)`.
+fn char_width(c: CodePoint) -> Option<usize> {
+    let c = c.to_u32();
+    // C0 controls, DEL and the C1 range (U+007F..=U+009F) have no printable
+    // width. U+00A0 (NBSP) is a printable width-1 character, not a control.
+    if c < 0x20 || (0x7f..=0x9f).contains(&c) {
+        return None;
+    }
+    if in_table(c, ZERO_WIDTH) {
+        Some(0)
+    } else if in_table(c, WIDE) {
+        Some(2)
+    } else {
+        Some(1)
+    }
+}
+
+// NOTE: the property tables below (`WIDE`, `ZERO_WIDTH`, the Grapheme_Cluster_Break
+// sets and `EXTENDED_PICTOGRAPHIC`) are compact, block-granularity
+// approximations of the Unicode data — not the per-code-point tables a
+// conformant UAX #29 / East Asian Width implementation ships. They cover the
+// common cases cheaply but over- or under-classify at the edges of some blocks
+// (e.g. the whole Dingbats block is treated as Extended_Pictographic). Treat
+// their results as best-effort, not authoritative.
+
+/// Binary search `c` against a sorted, non-overlapping table of inclusive
+/// code-point ranges.
+fn in_table(c: u32, table: &[(u32, u32)]) -> bool {
+    table
+        .binary_search_by(|&(lo, hi)| {
+            if c < lo {
+                core::cmp::Ordering::Greater
+            } else if c > hi {
+                core::cmp::Ordering::Less
+            } else {
+                core::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Code points with East Asian Width `W` (Wide) or `F` (Fullwidth).
+#[rustfmt::skip]
+static WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115f),   // Hangul Jamo
+    (0x2329, 0x232a),   // angle brackets
+    (0x2e80, 0x303e),   // CJK radicals .. CJK symbols
+    (0x3041, 0x33ff),   // Hiragana .. CJK compatibility
+    (0x3400, 0x4dbf),   // CJK Unified Ideographs Extension A
+    (0x4e00, 0x9fff),   // CJK Unified Ideographs
+    (0xa000, 0xa4cf),   // Yi Syllables / Radicals
+    (0xa960, 0xa97f),   // Hangul Jamo Extended-A
+    (0xac00, 0xd7a3),   // Hangul Syllables
+    (0xf900, 0xfaff),   // CJK Compatibility Ideographs
+    (0xfe10, 0xfe19),   // Vertical Forms
+    (0xfe30, 0xfe6f),   // CJK Compatibility / Small Form Variants
+    (0xff00, 0xff60),   // Fullwidth Forms
+    (0xffe0, 0xffe6),   // Fullwidth signs
+    (0x1b000, 0x1b16f), // Kana Supplement / Extended-A
+    (0x1f004, 0x1f004), // mahjong red dragon
+    (0x1f0cf, 0x1f0cf), // playing card black joker
+    (0x1f18e, 0x1f18e), // negative squared AB
+    (0x1f191, 0x1f19a), // squared symbols
+    (0x1f200, 0x1f2ff), // enclosed ideographic supplement
+    (0x1f300, 0x1f64f), // Miscellaneous Symbols and Pictographs / Emoticons
+    (0x1f900, 0x1f9ff), // Supplemental Symbols and Pictographs
+    (0x1fa00, 0x1faff), // Symbols and Pictographs Extended-A
+    (0x20000, 0x3fffd), // CJK Unified Ideographs Extension B and beyond
+];
+
+/// Combining marks and other code points that occupy no column.
+#[rustfmt::skip]
+static ZERO_WIDTH: &[(u32, u32)] = &[
+    (0x0300, 0x036f),   // Combining Diacritical Marks
+    (0x0483, 0x0489),   // Cyrillic combining marks
+    (0x0591, 0x05bd),   // Hebrew points
+    (0x05bf, 0x05bf),
+    (0x05c1, 0x05c2),
+    (0x05c4, 0x05c5),
+    (0x05c7, 0x05c7),
+    (0x0610, 0x061a),   // Arabic marks
+    (0x064b, 0x065f),
+    (0x0670, 0x0670),
+    (0x06d6, 0x06dc),
+    (0x06df, 0x06e4),
+    (0x06e7, 0x06e8),
+    (0x06ea, 0x06ed),
+    (0x0711, 0x0711),   // Syriac
+    (0x0730, 0x074a),
+    (0x07a6, 0x07b0),   // Thaana
+    (0x07eb, 0x07f3),   // NKo
+    (0x0900, 0x0903),   // Devanagari signs
+    (0x093a, 0x093c),
+    (0x0941, 0x0948),
+    (0x094d, 0x094d),
+    (0x0951, 0x0957),
+    (0x1ab0, 0x1aff),   // Combining Diacritical Marks Extended
+    (0x1dc0, 0x1dff),   // Combining Diacritical Marks Supplement
+    (0x200b, 0x200f),   // zero-width space .. directional marks
+    (0x2028, 0x202e),   // line/paragraph separators, directional embeddings
+    (0x2060, 0x2064),   // word joiner .. invisible operators
+    (0x20d0, 0x20ff),   // Combining Diacritical Marks for Symbols
+    (0xfe00, 0xfe0f),   // variation selectors
+    (0xfe20, 0xfe2f),   // Combining Half Marks
+    (0xfeff, 0xfeff),   // zero-width no-break space (BOM)
+    (0xfff9, 0xfffb),   // interlinear annotation anchors
+    (0xe0100, 0xe01ef), // variation selectors supplement
+];
+
+/// Iterator over the extended grapheme clusters (UAX #29) of a [`Wtf8`] string,
+/// yielding each cluster as a `&Wtf8` sub-slice. See [`graphemes`].
+#[derive(Debug, Clone)]
+pub struct Graphemes<'a> {
+    rest: &'a Wtf8,
+}
+
+/// Iterate the extended grapheme clusters of `w` as `&Wtf8` sub-slices,
+/// applying the UAX #29 extended grapheme break rules directly over its code
+/// points.
+#[inline]
+pub fn graphemes(w: &Wtf8) -> Graphemes<'_> {
+    Graphemes { rest: w }
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = &'a Wtf8;
+
+    fn next(&mut self) -> Option<&'a Wtf8> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let mut iter = self.rest.code_point_indices();
+        let (_, first) = iter.next().unwrap();
+        let mut prev = grapheme_category(first.to_u32());
+
+        // Running break state (GB11 emoji ZWJ sequences, GB12/13 regional
+        // indicator parity).
+        let mut ri_count = usize::from(prev == Gcb::RegionalIndicator);
+        let mut extpict_run = prev == Gcb::ExtendedPictographic;
+        let mut zwj_pending = false;
+
+        let mut boundary = self.rest.len();
+        for (i, c) in iter {
+            let cat = grapheme_category(c.to_u32());
+            if should_break(prev, cat, ri_count, zwj_pending) {
+                boundary = i;
+                break;
+            }
+            match cat {
+                Gcb::ExtendedPictographic => {
+                    extpict_run = true;
+                    zwj_pending = false;
+                }
+                Gcb::Extend => zwj_pending = false,
+                Gcb::ZWJ => {
+                    zwj_pending = extpict_run;
+                    extpict_run = false;
+                }
+                _ => {
+                    extpict_run = false;
+                    zwj_pending = false;
+                }
+            }
+            ri_count = if cat == Gcb::RegionalIndicator {
+                ri_count + 1
+            } else {
+                0
+            };
+            prev = cat;
+        }
+
+        let cluster = &self.rest[..boundary];
+        self.rest = &self.rest[boundary..];
+        Some(cluster)
+    }
+}
+
+/// Grapheme_Cluster_Break property value, plus the Extended_Pictographic
+/// property folded in as its own variant (used only by rule GB11).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Gcb {
+    Other,
+    CR,
+    LF,
+    Control,
+    Extend,
+    ZWJ,
+    RegionalIndicator,
+    Prepend,
+    SpacingMark,
+    L,
+    V,
+    T,
+    LV,
+    LVT,
+    ExtendedPictographic,
+}
+
+/// Decide whether there is a grapheme boundary between two adjacent code points
+/// of categories `prev` and `next`. `ri_count` is the number of consecutive
+/// Regional_Indicator code points ending at `prev` (for GB12/13), and
+/// `zwj_pending` is set when `prev` is a ZWJ that continues an
+/// `Extended_Pictographic Extend*` run (for GB11).
+fn should_break(prev: Gcb, next: Gcb, ri_count: usize, zwj_pending: bool) -> bool {
+    use Gcb::*;
+    match (prev, next) {
+        // GB3: do not break within a CRLF pair.
+        (CR, LF) => false,
+        // GB4 / GB5: always break around Control, CR and LF otherwise.
+        (CR | LF | Control, _) | (_, CR | LF | Control) => true,
+        // GB6 / GB7 / GB8: keep Hangul syllable sequences together.
+        (L, L | V | LV | LVT) => false,
+        (LV | V, V | T) => false,
+        (LVT | T, T) => false,
+        // GB9 / GB9a: do not break before Extend, ZWJ or SpacingMark.
+        (_, Extend | ZWJ | SpacingMark) => false,
+        // GB9b: do not break after Prepend.
+        (Prepend, _) => false,
+        // GB11: keep emoji ZWJ sequences together.
+        (_, ExtendedPictographic) if zwj_pending => false,
+        // GB12 / GB13: break between regional indicators only on even boundaries.
+        (RegionalIndicator, RegionalIndicator) if ri_count % 2 == 1 => false,
+        // GB999: otherwise, break.
+        _ => true,
+    }
+}
+
+/// Classify a code point by its Grapheme_Cluster_Break property.
+fn grapheme_category(c: u32) -> Gcb {
+    match c {
+        0x0d => return Gcb::CR,
+        0x0a => return Gcb::LF,
+        0x200d => return Gcb::ZWJ,
+        0x1f1e6..=0x1f1ff => return Gcb::RegionalIndicator,
+        _ => {}
+    }
+    // Hangul syllables: LV when the trailing-jamo index is zero, else LVT.
+    if (0xac00..=0xd7a3).contains(&c) {
+        return if (c - 0xac00) % 28 == 0 {
+            Gcb::LV
+        } else {
+            Gcb::LVT
+        };
+    }
+    if in_table(c, HANGUL_L) {
+        Gcb::L
+    } else if in_table(c, HANGUL_V) {
+        Gcb::V
+    } else if in_table(c, HANGUL_T) {
+        Gcb::T
+    } else if in_table(c, CONTROL) {
+        Gcb::Control
+    } else if in_table(c, EXTEND) {
+        Gcb::Extend
+    } else if in_table(c, SPACING_MARK) {
+        Gcb::SpacingMark
+    } else if in_table(c, PREPEND) {
+        Gcb::Prepend
+    } else if in_table(c, EXTENDED_PICTOGRAPHIC) {
+        Gcb::ExtendedPictographic
+    } else {
+        Gcb::Other
+    }
+}
+
+#[rustfmt::skip]
+static HANGUL_L: &[(u32, u32)] = &[(0x1100, 0x115f), (0xa960, 0xa97c)];
+#[rustfmt::skip]
+static HANGUL_V: &[(u32, u32)] = &[(0x1160, 0x11a7), (0xd7b0, 0xd7c6)];
+#[rustfmt::skip]
+static HANGUL_T: &[(u32, u32)] = &[(0x11a8, 0x11ff), (0xd7cb, 0xd7fb)];
+
+/// Grapheme_Cluster_Break = Control (excluding CR, LF and ZWJ, handled above).
+#[rustfmt::skip]
+static CONTROL: &[(u32, u32)] = &[
+    (0x0000, 0x0009),
+    (0x000b, 0x000c),
+    (0x000e, 0x001f),
+    (0x007f, 0x009f),
+    (0x00ad, 0x00ad),   // soft hyphen
+    (0x061c, 0x061c),   // arabic letter mark
+    (0x180e, 0x180e),   // mongolian vowel separator
+    (0x200b, 0x200b),   // zero-width space
+    (0x2028, 0x202e),   // line/paragraph separators, directional embeddings
+    (0x2060, 0x2064),   // word joiner .. invisible operators
+    (0xfeff, 0xfeff),   // zero-width no-break space
+    (0xfff0, 0xfffb),   // specials (interlinear annotation, etc.)
+];
+
+/// Grapheme_Cluster_Break = Extend (combining marks and variation selectors).
+#[rustfmt::skip]
+static EXTEND: &[(u32, u32)] = &[
+    (0x0300, 0x036f),   // Combining Diacritical Marks
+    (0x0483, 0x0489),
+    (0x0591, 0x05bd),
+    (0x05bf, 0x05bf),
+    (0x05c1, 0x05c2),
+    (0x05c4, 0x05c5),
+    (0x05c7, 0x05c7),
+    (0x0610, 0x061a),
+    (0x064b, 0x065f),
+    (0x0670, 0x0670),
+    (0x06d6, 0x06dc),
+    (0x06df, 0x06e4),
+    (0x06e7, 0x06e8),
+    (0x06ea, 0x06ed),
+    (0x0711, 0x0711),
+    (0x0730, 0x074a),
+    (0x07a6, 0x07b0),
+    (0x07eb, 0x07f3),
+    (0x0900, 0x0902),
+    (0x093a, 0x093a),
+    (0x093c, 0x093c),
+    (0x0941, 0x0948),
+    (0x094d, 0x094d),
+    (0x0951, 0x0957),
+    (0x1ab0, 0x1aff),   // Combining Diacritical Marks Extended
+    (0x1dc0, 0x1dff),   // Combining Diacritical Marks Supplement
+    (0x20d0, 0x20ff),   // Combining Diacritical Marks for Symbols
+    (0xfe00, 0xfe0f),   // variation selectors
+    (0xfe20, 0xfe2f),   // Combining Half Marks
+    (0xe0100, 0xe01ef), // variation selectors supplement
+];
+
+/// Grapheme_Cluster_Break = SpacingMark (a curated subset of common Mc marks).
+#[rustfmt::skip]
+static SPACING_MARK: &[(u32, u32)] = &[
+    (0x0903, 0x0903),
+    (0x093b, 0x093b),
+    (0x093e, 0x0940),
+    (0x0949, 0x094c),
+    (0x094e, 0x094f),
+    (0x0982, 0x0983),
+    (0x09be, 0x09c0),
+    (0x0bbe, 0x0bbf),
+    (0x0cbe, 0x0cbe),
+];
+
+/// Grapheme_Cluster_Break = Prepend.
+#[rustfmt::skip]
+static PREPEND: &[(u32, u32)] = &[
+    (0x0600, 0x0605),   // Arabic number signs
+    (0x06dd, 0x06dd),
+    (0x070f, 0x070f),
+    (0x0d4e, 0x0d4e),
+    (0x110bd, 0x110bd),
+    (0x111c2, 0x111c3),
+];
+
+/// Extended_Pictographic property (emoji code points, approximated by block).
+#[rustfmt::skip]
+static EXTENDED_PICTOGRAPHIC: &[(u32, u32)] = &[
+    (0x00a9, 0x00a9),   // copyright
+    (0x00ae, 0x00ae),   // registered
+    (0x2122, 0x2122),   // trade mark
+    (0x2139, 0x2139),   // information source
+    (0x2600, 0x27bf),   // Miscellaneous Symbols / Dingbats
+    (0x1f000, 0x1f0ff), // Mahjong / Dominoes / Playing Cards
+    (0x1f100, 0x1f1ff), // Enclosed Alphanumeric Supplement
+    (0x1f200, 0x1f2ff), // Enclosed Ideographic Supplement
+    (0x1f300, 0x1f5ff), // Miscellaneous Symbols and Pictographs
+    (0x1f600, 0x1f64f), // Emoticons
+    (0x1f680, 0x1f6ff), // Transport and Map Symbols
+    (0x1f900, 0x1f9ff), // Supplemental Symbols and Pictographs
+    (0x1fa00, 0x1faff), // Symbols and Pictographs Extended-A
+];
+
 impl std::fmt::Display for StrData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.data.fmt(f)
+        self.as_wtf8().fmt(f)
     }
 }
 
@@ -447,99 +1033,107 @@ pub fn to_ascii(value: &str) -> AsciiString {
 }
 
 pub mod levenshtein {
-    use std::{cell::RefCell, thread_local};
+    use smallvec::SmallVec;
 
     pub const MOVE_COST: usize = 2;
     const CASE_COST: usize = 1;
-    const MAX_STRING_SIZE: usize = 40;
 
-    fn substitution_cost(mut a: u8, mut b: u8) -> usize {
-        if (a & 31) != (b & 31) {
-            return MOVE_COST;
-        }
+    /// Number of code points kept inline before spilling to the heap; most
+    /// identifiers in "did you mean" suggestions are shorter than this.
+    const STACK_LEN: usize = 20;
+
+    /// Cost of substituting one code point for another under the weighted model:
+    /// free when equal, [`CASE_COST`] when they differ only by Unicode case
+    /// folding, and [`MOVE_COST`] otherwise.
+    fn substitution_cost(a: char, b: char) -> usize {
         if a == b {
             return 0;
         }
-        if a.is_ascii_uppercase() {
-            a += b'a' - b'A';
-        }
-        if b.is_ascii_uppercase() {
-            b += b'a' - b'A';
+        if a.to_lowercase().eq(b.to_lowercase()) {
+            CASE_COST
+        } else {
+            MOVE_COST
         }
-        if a == b { CASE_COST } else { MOVE_COST }
     }
 
     pub fn levenshtein_distance(a: &str, b: &str, max_cost: usize) -> usize {
-        thread_local! {
-            static BUFFER: RefCell<[usize; MAX_STRING_SIZE]> = const { RefCell::new([0usize; MAX_STRING_SIZE]) };
-        }
-
         if a == b {
             return 0;
         }
 
-        let (mut a_bytes, mut b_bytes) = (a.as_bytes(), b.as_bytes());
-        let (mut a_begin, mut a_end) = (0usize, a.len());
-        let (mut b_begin, mut b_end) = (0usize, b.len());
+        let a: SmallVec<[char; STACK_LEN]> = a.chars().collect();
+        let b: SmallVec<[char; STACK_LEN]> = b.chars().collect();
 
-        while a_end > 0 && b_end > 0 && (a_bytes[a_begin] == b_bytes[b_begin]) {
-            a_begin += 1;
-            b_begin += 1;
-            a_end -= 1;
-            b_end -= 1;
+        // strip the common prefix, then the common suffix
+        let mut begin = 0;
+        while begin < a.len() && begin < b.len() && a[begin] == b[begin] {
+            begin += 1;
         }
-        while a_end > 0
-            && b_end > 0
-            && (a_bytes[a_begin + a_end - 1] == b_bytes[b_begin + b_end - 1])
-        {
+        let (mut a_end, mut b_end) = (a.len(), b.len());
+        while a_end > begin && b_end > begin && a[a_end - 1] == b[b_end - 1] {
             a_end -= 1;
             b_end -= 1;
         }
-        if a_end == 0 || b_end == 0 {
-            return (a_end + b_end) * MOVE_COST;
-        }
-        if a_end > MAX_STRING_SIZE || b_end > MAX_STRING_SIZE {
-            return max_cost + 1;
+        let a = &a[begin..a_end];
+        let b = &b[begin..b_end];
+        if a.is_empty() || b.is_empty() {
+            return (a.len() + b.len()) * MOVE_COST;
         }
 
-        if b_end < a_end {
-            std::mem::swap(&mut a_bytes, &mut b_bytes);
-            std::mem::swap(&mut a_begin, &mut b_begin);
-            std::mem::swap(&mut a_end, &mut b_end);
-        }
+        // index the shorter operand in the inner loop
+        let (a, b) = if a.len() > b.len() { (b, a) } else { (a, b) };
+        let (n, m) = (a.len(), b.len());
 
-        if (b_end - a_end) * MOVE_COST > max_cost {
+        if (m - n) * MOVE_COST > max_cost {
             return max_cost + 1;
         }
 
-        BUFFER.with(|buffer| {
-            let mut buffer = buffer.borrow_mut();
-            for i in 0..a_end {
-                buffer[i] = (i + 1) * MOVE_COST;
+        // Only the diagonal band of this half-width around the main diagonal can
+        // contain a path cheaper than `max_cost`, so that is all we compute.
+        let band = max_cost.div_ceil(MOVE_COST) + (m - n);
+        // Anything at or above this is over budget; clamp to avoid overflow.
+        let inf = max_cost + 1;
+
+        // Rolling DP over the `a` dimension. `prev[i]` is the distance between
+        // `a[..i]` and `b[..j-1]`; cells outside the band hold `inf`.
+        let mut prev = vec![inf; n + 1];
+        prev.iter_mut()
+            .take(band.min(n) + 1)
+            .enumerate()
+            .for_each(|(i, cell)| *cell = i * MOVE_COST);
+        let mut cur = vec![inf; n + 1];
+
+        for (j_index, &b_char) in b.iter().enumerate() {
+            let j = j_index + 1;
+            let lo = j.saturating_sub(band);
+            let hi = (j + band).min(n);
+            if lo > 0 {
+                // sentinel left of the band for the `cur[i - 1]` read below
+                cur[lo - 1] = inf;
             }
-
-            let mut result = 0usize;
-            for (b_index, b_code) in b_bytes[b_begin..(b_begin + b_end)].iter().enumerate() {
-                result = b_index * MOVE_COST;
-                let mut distance = result;
-                let mut minimum = usize::MAX;
-                for (a_index, a_code) in a_bytes[a_begin..(a_begin + a_end)].iter().enumerate() {
-                    let substitute = distance + substitution_cost(*b_code, *a_code);
-                    distance = buffer[a_index];
-                    let insert_delete = usize::min(result, distance) + MOVE_COST;
-                    result = usize::min(insert_delete, substitute);
-
-                    buffer[a_index] = result;
-                    if result < minimum {
-                        minimum = result;
-                    }
-                }
-                if minimum > max_cost {
-                    return max_cost + 1;
-                }
+            let mut minimum = inf;
+            for i in lo..=hi {
+                let cost = if i == 0 {
+                    j * MOVE_COST
+                } else {
+                    let substitute =
+                        prev[i - 1].saturating_add(substitution_cost(a[i - 1], b_char));
+                    let insert_delete = usize::min(prev[i], cur[i - 1]).saturating_add(MOVE_COST);
+                    usize::min(substitute, insert_delete).min(inf)
+                };
+                cur[i] = cost;
+                minimum = minimum.min(cost);
             }
-            result
-        })
+            if hi < n {
+                // sentinel right of the band for the next column's reads
+                cur[hi + 1] = inf;
+            }
+            if minimum > max_cost {
+                return max_cost + 1;
+            }
+            std::mem::swap(&mut prev, &mut cur);
+        }
+        prev[n]
     }
 }
 
@@ -574,6 +1168,44 @@ pub fn expandtabs(input: &str, tab_size: usize) -> String {
     expanded_str
 }
 
+/// Replace all tabs in a string with spaces like [`expandtabs`], but advance
+/// the column counter by each character's East Asian display width so that tab
+/// stops line up when `input` contains wide glyphs. Characters without a
+/// defined width (controls) advance the column counter by zero.
+pub fn expandtabs_width(input: &str, tab_size: usize) -> String {
+    let tab_stop = tab_size;
+    let mut expanded_str = String::with_capacity(input.len());
+    let mut tab_size = tab_stop;
+    let mut col_count = 0usize;
+    for ch in input.chars() {
+        match ch {
+            '\t' => {
+                // saturating: with a zero tab size the tab stop never advances
+                // below, so the column can already be past it.
+                let num_spaces = tab_size.saturating_sub(col_count);
+                col_count += num_spaces;
+                let expand = " ".repeat(num_spaces);
+                expanded_str.push_str(&expand);
+            }
+            '\r' | '\n' => {
+                expanded_str.push(ch);
+                col_count = 0;
+                tab_size = 0;
+            }
+            _ => {
+                expanded_str.push(ch);
+                col_count += char_width(CodePoint::from(ch)).unwrap_or(0);
+            }
+        }
+        // a wide glyph can advance the column by more than one, so loop the tab
+        // stop until it is past the cursor again (guarding a zero tab size).
+        while tab_stop > 0 && col_count >= tab_size {
+            tab_size += tab_stop;
+        }
+    }
+    expanded_str
+}
+
 /// Creates an [`AsciiStr`][ascii::AsciiStr] from a string literal, throwing a compile error if the
 /// literal isn't actually ascii.
 ///
@@ -610,4 +1242,112 @@ mod tests {
         let s = "0😀😃😄😁😆😅😂🤣9";
         assert_eq!(get_chars(s, 3..7), "😄😁😆😅");
     }
+
+    #[test]
+    fn test_width() {
+        let ascii: StrData = Box::<str>::from("hello").into();
+        assert_eq!(ascii.width(), Some(5));
+
+        let wide: StrData = Box::<str>::from("한글").into();
+        assert_eq!(wide.width(), Some(4));
+
+        // base latin letter + combining acute accent (NFD "é")
+        let combining: StrData = Box::<str>::from("e\u{0301}").into();
+        assert_eq!(combining.width(), Some(1));
+
+        let control: StrData = Box::<str>::from("a\x07b").into();
+        assert_eq!(control.width(), None);
+
+        // NBSP is a printable width-1 character, not a control
+        let nbsp: StrData = Box::<str>::from("a\u{a0}b").into();
+        assert_eq!(nbsp.width(), Some(3));
+    }
+
+    #[test]
+    fn test_grapheme_len() {
+        // base letter + combining acute accent is one cluster
+        let combining: StrData = Box::<str>::from("e\u{0301}").into();
+        assert_eq!(combining.grapheme_len(), 1);
+
+        // a flag is a pair of regional indicators, kept together
+        let flag: StrData = Box::<str>::from("a🇺🇸b").into();
+        assert_eq!(flag.grapheme_len(), 3);
+
+        // CRLF never splits
+        let crlf: StrData = Box::<str>::from("a\r\nb").into();
+        assert_eq!(crlf.grapheme_len(), 3);
+
+        // family emoji joined by ZWJ stays a single cluster
+        let family: StrData = Box::<str>::from("👨\u{200d}👩\u{200d}👧").into();
+        assert_eq!(family.grapheme_len(), 1);
+    }
+
+    #[test]
+    fn test_fixed_width_store() {
+        let ascii: StrData = Box::<str>::from("abc").into();
+        assert_eq!(ascii.kind(), StrKind::Ascii);
+        assert_eq!(ascii.char_len(), 3);
+
+        // all code points ≤ U+00FF but not ASCII: stored one byte each (Latin-1
+        // representation) while keeping the Utf8 kind
+        let latin1: StrData = Box::<str>::from("café").into();
+        assert_eq!(latin1.kind(), StrKind::Utf8);
+        assert_eq!(latin1.char_len(), 4);
+        assert_eq!(latin1.nth_char(3), CodePoint::from('é'));
+
+        // needs UCS-2
+        let ucs2: StrData = Box::<str>::from("ab‰").into();
+        assert_eq!(ucs2.kind(), StrKind::Utf8);
+        assert_eq!(ucs2.nth_char(2), CodePoint::from('‰'));
+
+        // needs UCS-4
+        let ucs4: StrData = Box::<str>::from("a😀b").into();
+        assert_eq!(ucs4.char_len(), 3);
+        assert_eq!(ucs4.nth_char(1), CodePoint::from('😀'));
+        assert_eq!(ucs4.as_str(), Some("a😀b"));
+    }
+
+    #[test]
+    fn test_utf16_roundtrip() {
+        // 'A', a 😀 surrogate pair, then a lone high surrogate.
+        let units = [0x0041u16, 0xd83d, 0xde00, 0xd800];
+        let s = StrData::from_utf16(&units);
+        assert_eq!(s.kind(), StrKind::Wtf8);
+        assert_eq!(s.char_len(), 3);
+        assert_eq!(s.encode_utf16(), units);
+
+        // Well-formed input stays a normal UTF-8 string.
+        let ascii = StrData::from_utf16(&[0x68, 0x69]);
+        assert_eq!(ascii.kind(), StrKind::Ascii);
+        assert_eq!(ascii.as_str(), Some("hi"));
+    }
+
+    #[test]
+    fn test_levenshtein_distance() {
+        use levenshtein::{MOVE_COST, levenshtein_distance};
+
+        assert_eq!(levenshtein_distance("abc", "abc", 100), 0);
+        // three edits at MOVE_COST each
+        assert_eq!(levenshtein_distance("kitten", "sitting", 100), 3 * MOVE_COST);
+        // differ only by case
+        assert_eq!(levenshtein_distance("Foo", "foo", 100), CASE_COST_TEST);
+        // capped once the band minimum exceeds the budget
+        assert_eq!(levenshtein_distance("abc", "xyz", 2), 2 + 1);
+        // no 40-char ceiling: long names still get an exact small distance
+        let a = "a".repeat(50);
+        let b = format!("{}b", "a".repeat(49));
+        assert_eq!(levenshtein_distance(&a, &b, 100), MOVE_COST);
+    }
+
+    // CASE_COST is private to the levenshtein module; mirror its value here.
+    const CASE_COST_TEST: usize = 1;
+
+    #[test]
+    fn test_expandtabs_width() {
+        // "한" is two columns wide, so the tab fills the remaining two columns.
+        assert_eq!(expandtabs_width("한\tx", 4), "한  x");
+        assert_eq!(expandtabs_width("ab\tx", 4), "ab  x");
+        // wide glyphs overshooting a small tab stop must not underflow-panic
+        assert_eq!(expandtabs_width("한한\t", 1), "한한 ");
+    }
 }